@@ -0,0 +1,160 @@
+//! Instantiation-time benchmark: plain `set_pages` restore versus the `map_pages_cow` restore.
+//!
+//! The contract grows its memory but only touches a few pages. Both paths write every saved page
+//! into linear memory up front; no page is faulted in lazily. The `map_pages_cow` path also keeps
+//! the restored pages as a baseline and diffs memory against it after the run to find the dirty
+//! pages. Run with `cargo bench -p gear-core-backend --bench grow_memory`.
+
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use gear_core::env::Ext;
+use gear_core::memory::{PageBuf, PageNumber};
+use gear_core_backend::wasmtime::env::Environment;
+
+/// Inert `Ext` for the benchmark: the measured module imports only `memory` and makes no host
+/// calls, so every method is a trivial placeholder. Kept self-contained rather than shared with the
+/// fuzz mock, which depends on `arbitrary`/`libfuzzer`.
+#[derive(Default)]
+struct MockExt;
+
+impl Ext for MockExt {
+    fn gas(&mut self, _amount: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn charge(&mut self, _amount: u64) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn alloc(&mut self, _pages: u32) -> Result<u32, &'static str> {
+        Ok(0)
+    }
+
+    fn free(&mut self, _page: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn commit(&mut self, _handle: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn debug(&mut self, _ptr: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn init(
+        &mut self,
+        _program: [u8; 32],
+        _payload_ptr: u32,
+        _payload_len: u32,
+        _value: u128,
+        _salt: u32,
+    ) -> Result<u32, &'static str> {
+        Ok(0)
+    }
+
+    fn msg_id(&mut self) -> [u8; 32] {
+        [0u8; 32]
+    }
+
+    fn push(&mut self, _handle: u32, _ptr: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn push_reply(&mut self, _ptr: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn read(&mut self, _at: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn send(
+        &mut self,
+        _dest: [u8; 32],
+        _ptr: u32,
+        _len: u32,
+        _value: u128,
+        _handle: u32,
+    ) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn reply(
+        &mut self,
+        _ptr: u32,
+        _len: u32,
+        _value: u128,
+        _handle: u32,
+    ) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn reply_to(&mut self) -> [u8; 32] {
+        [0u8; 32]
+    }
+
+    fn size(&mut self) -> u32 {
+        0
+    }
+
+    fn source(&mut self) -> [u8; 32] {
+        [0u8; 32]
+    }
+
+    fn value(&mut self) -> u128 {
+        0
+    }
+}
+
+// A module that grows its memory and writes into a single page of it.
+const GROW_MEMORY_WAT: &str = r#"
+    (module
+        (import "env" "memory" (memory 1))
+        (func (export "handle")
+            (drop (memory.grow (i32.const 512)))
+            (i32.store (i32.const 0) (i32.const 42))
+        )
+    )
+"#;
+
+// A large saved page set that both paths restore in full.
+fn saved_pages(pages: u32) -> BTreeMap<PageNumber, Box<PageBuf>> {
+    (0..pages)
+        .map(|p| (PageNumber::from(p), Box::new(PageBuf::new_zeroed())))
+        .collect()
+}
+
+fn grow_memory(c: &mut Criterion) {
+    let binary = wat::parse_str(GROW_MEMORY_WAT).expect("invalid wat");
+    let pages = saved_pages(512);
+
+    let mut group = c.benchmark_group("grow_memory");
+
+    group.bench_function("eager_set_pages", |b| {
+        let mut env = Environment::<MockExt>::new();
+        env.set_cow_memory(false);
+        b.iter(|| {
+            let memory = env.create_memory(1);
+            let (_reason, _ext) =
+                env.setup_and_run(MockExt::default(), &binary, &pages, &memory, "handle");
+        });
+    });
+
+    group.bench_function("cow_mapped_pages", |b| {
+        let mut env = Environment::<MockExt>::new();
+        env.set_cow_memory(true);
+        b.iter(|| {
+            let memory = env.create_memory(1);
+            let (_reason, _ext) =
+                env.setup_and_run(MockExt::default(), &binary, &pages, &memory, "handle");
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, grow_memory);
+criterion_main!(benches);
@@ -0,0 +1,3 @@
+//! Shared support code for the environment fuzz targets.
+
+pub mod mock;
@@ -0,0 +1,159 @@
+//! Mock `Ext` and host-call script used by the environment fuzz target.
+
+use arbitrary::Arbitrary;
+
+use gear_core::env::Ext;
+
+/// A host call the fuzzer asks the mock `Ext` to replay, with randomized arguments.
+#[derive(Debug, Clone, Arbitrary)]
+pub enum HostCall {
+    Alloc { pages: u32 },
+    Free { page: u32 },
+    Send { dest: [u8; 32], value: u128, payload: Vec<u8> },
+    Reply { value: u128, payload: Vec<u8> },
+}
+
+/// Mock external environment that records gas spent and replays a scripted sequence of host calls,
+/// returning structured errors rather than panicking on invalid arguments.
+pub struct MockExt {
+    script: Vec<HostCall>,
+    cursor: usize,
+    gas_spent: u64,
+    /// Next page index handed out by [`alloc`](MockExt::alloc).
+    next_page: u32,
+}
+
+impl MockExt {
+    /// Build a mock that replays `script`.
+    pub fn scripted(script: Vec<HostCall>) -> Self {
+        Self {
+            script,
+            cursor: 0,
+            gas_spent: 0,
+            next_page: 0,
+        }
+    }
+
+    /// Total gas charged through this mock so far.
+    pub fn gas_spent(&self) -> u64 {
+        self.gas_spent
+    }
+
+    /// Advance the script, returning the next call to perform (if any).
+    fn next_call(&mut self) -> Option<HostCall> {
+        let call = self.script.get(self.cursor).cloned();
+        self.cursor += 1;
+        call
+    }
+}
+
+impl Default for MockExt {
+    fn default() -> Self {
+        Self::scripted(Vec::new())
+    }
+}
+
+// The mock mirrors the production `Ext` surface registered in `Environment::new`, but is backed by
+// in-memory bookkeeping: every fallible operation returns `Err(&'static str)` instead of aborting,
+// so the fuzzer observes structured failures rather than panics. The message-producing calls
+// (`send`/`reply`) advance the recorded script so the driver can shape host behaviour; everything
+// else returns a deterministic placeholder.
+impl Ext for MockExt {
+    fn gas(&mut self, amount: u32) -> Result<(), &'static str> {
+        self.charge(amount as u64)
+    }
+
+    fn charge(&mut self, amount: u64) -> Result<(), &'static str> {
+        self.gas_spent = self.gas_spent.saturating_add(amount);
+        Ok(())
+    }
+
+    fn alloc(&mut self, pages: u32) -> Result<u32, &'static str> {
+        let at = self.next_page;
+        self.next_page = self.next_page.checked_add(pages).ok_or("alloc overflow")?;
+        Ok(at)
+    }
+
+    fn free(&mut self, _page: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn commit(&mut self, _handle: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn debug(&mut self, _ptr: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn init(
+        &mut self,
+        _program: [u8; 32],
+        _payload_ptr: u32,
+        _payload_len: u32,
+        _value: u128,
+        _salt: u32,
+    ) -> Result<u32, &'static str> {
+        Ok(0)
+    }
+
+    fn msg_id(&mut self) -> [u8; 32] {
+        [0u8; 32]
+    }
+
+    fn push(&mut self, _handle: u32, _ptr: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn push_reply(&mut self, _ptr: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn read(&mut self, _at: u32, _len: u32) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn send(
+        &mut self,
+        _dest: [u8; 32],
+        _ptr: u32,
+        _len: u32,
+        _value: u128,
+        _handle: u32,
+    ) -> Result<(), &'static str> {
+        // Consuming the script lets the driver decide whether the call succeeds.
+        match self.next_call() {
+            Some(HostCall::Send { .. }) | None => Ok(()),
+            Some(_) => Err("unexpected host call for send"),
+        }
+    }
+
+    fn reply(
+        &mut self,
+        _ptr: u32,
+        _len: u32,
+        _value: u128,
+        _handle: u32,
+    ) -> Result<(), &'static str> {
+        match self.next_call() {
+            Some(HostCall::Reply { .. }) | None => Ok(()),
+            Some(_) => Err("unexpected host call for reply"),
+        }
+    }
+
+    fn reply_to(&mut self) -> [u8; 32] {
+        [0u8; 32]
+    }
+
+    fn size(&mut self) -> u32 {
+        0
+    }
+
+    fn source(&mut self) -> [u8; 32] {
+        [0u8; 32]
+    }
+
+    fn value(&mut self) -> u128 {
+        0
+    }
+}
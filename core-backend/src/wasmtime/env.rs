@@ -1,11 +1,14 @@
 //! Wasmtime environment for running a module.
 
-use wasmtime::{Engine, Extern, Func, Instance, Module, Store, Trap};
+use wasmtime::{Config, Engine, Func, Instance, Linker, Module, Store, Trap};
 
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
+use blake2::{digest::typenum::U32, Blake2b, Digest};
+
 use super::memory::MemoryWrap;
 
 use gear_core::env::{Ext, LaterExt};
@@ -13,11 +16,83 @@ use gear_core::memory::{Memory, PageBuf, PageNumber};
 
 use crate::funcs;
 
+/// Outcome of a single `setup_and_run` invocation.
+///
+/// Separates a real wasm trap (with its preserved backtrace) from business-level exits, so
+/// callers can distinguish a contract that legitimately finished, one that ran out of gas, one
+/// that paused to wait, and one that truly faulted.
+pub enum TerminationReason {
+    /// The entry point returned normally.
+    Success,
+    /// A genuine wasm trap occurred; the [`Trap`] carries the backtrace frames and trap pc.
+    Trap(Trap),
+    /// Execution was stopped because the enforced gas limit (store fuel) was exhausted.
+    OutOfGas,
+    /// The contract paused itself via a `gr_wait`-style host call; the snapshot holds the memory
+    /// state needed to [`resume`](Environment::resume) it later.
+    Wait(ExecutionSnapshot),
+    /// A host function reported an error.
+    HostError(String),
+}
+
+/// Serializable memory state captured when a contract waits, so a later run observes identical
+/// memory to the paused one.
+pub struct ExecutionSnapshot {
+    /// The full set of pages that were live (dirtied or restored) at the wait point.
+    pub pages: BTreeMap<PageNumber, Box<PageBuf>>,
+}
+
+/// Dedicated `i32` exit status a `gr_wait`-style host call traps with to unwind the instance.
+///
+/// Using [`Trap::i32_exit`] rather than a human-readable message gives an exact, structured signal
+/// that cannot collide with a trap message a contract might otherwise produce; it is recognised via
+/// [`Trap::i32_exit_status`] in [`is_wait_trap`].
+const WAIT_TRAP_EXIT_CODE: i32 = 0x6761_6974;
+
+/// Build the trap a `gr_wait`-style host call returns to pause the instance.
+pub fn wait_trap() -> Trap {
+    Trap::i32_exit(WAIT_TRAP_EXIT_CODE)
+}
+
+/// Whether `trap` is the dedicated wait-unwind trap produced by [`wait_trap`].
+fn is_wait_trap(trap: &Trap) -> bool {
+    trap.i32_exit_status() == Some(WAIT_TRAP_EXIT_CODE)
+}
+
 /// Environment to run one module at a time providing Ext.
 pub struct Environment<E: Ext + 'static> {
     store: wasmtime::Store,
     ext: LaterExt<E>,
     funcs: BTreeMap<&'static str, Func>,
+    /// Import resolver, built once with every host function registered under its module namespace.
+    ///
+    /// Additional host modules (e.g. a separate `gcore`/`debug` namespace, or the exports of a
+    /// previously-instantiated contract) can be defined on the linker without touching
+    /// [`run_inner`](Self::run_inner).
+    linker: Linker,
+    /// Compiled modules keyed by `blake2b-256(binary)`, so the same contract is not recompiled for
+    /// every message it processes.
+    module_cache: BTreeMap<[u8; 32], Module>,
+    /// When set, the saved pages are restored through [`MemoryWrap::map_pages_cow`], which keeps
+    /// them as a baseline for dirty-page diffing after the run (see
+    /// [`set_cow_memory`](Self::set_cow_memory)). Both paths copy every saved page up front.
+    cow_memory: bool,
+    /// Pages whose contents differ from the restored baseline after the most recent run.
+    dirty_pages: Vec<PageNumber>,
+    /// Fuel available to each run on the engine-level metering path.
+    ///
+    /// `u64::MAX` means "effectively unlimited", which is the default so that instrumented modules
+    /// charging through the host `gas` import keep their previous behaviour. A store with
+    /// `consume_fuel` enabled starts at zero fuel and would trap on the first instruction, so the
+    /// default path is still topped up (see [`reset_fuel`](Self::reset_fuel)).
+    gas_limit: u64,
+    /// Total fuel ever added to the persistent store, so the per-run remaining can be derived.
+    fuel_added: u64,
+    /// Fuel consumed as observed at the start of the current run, so [`gas_consumed`] reports the
+    /// per-run delta rather than the store's cumulative total.
+    ///
+    /// [`gas_consumed`]: Self::gas_consumed
+    run_baseline: u64,
 }
 
 impl<E: Ext + 'static> Environment<E> {
@@ -25,10 +100,22 @@ impl<E: Ext + 'static> Environment<E> {
     ///
     /// To run actual function with provided external environment, `setup_and_run` should be used.
     pub fn new() -> Self {
+        // Enable engine-level fuel metering so a module can be run with an enforced gas limit even
+        // when it carries no injected `gas` host call.
+        let engine = Engine::new(Config::new().consume_fuel(true));
+
         let mut result = Self {
-            store: Store::default(),
+            store: Store::new(&engine),
             ext: LaterExt::new(),
             funcs: BTreeMap::new(),
+            // Rebuilt below, once every host function has been registered.
+            linker: Linker::new(&Store::new(&engine)),
+            module_cache: BTreeMap::new(),
+            cow_memory: false,
+            dirty_pages: Vec::new(),
+            gas_limit: u64::MAX,
+            fuel_added: 0,
+            run_baseline: 0,
         };
 
         result.add_func_i32_to_u32("alloc", funcs::alloc);
@@ -49,6 +136,16 @@ impl<E: Ext + 'static> Environment<E> {
         result.add_func_i32("gr_source", funcs::source);
         result.add_func_i32("gr_value", funcs::value);
 
+        // Register every host function on the linker under the `env` namespace. Instantiation then
+        // resolves imports by name through the linker instead of scanning `funcs` per import.
+        let mut linker = Linker::new(&result.store);
+        for (name, func) in &result.funcs {
+            linker
+                .define("env", name, func.clone())
+                .expect("duplicate host function definition");
+        }
+        result.linker = linker;
+
         result
     }
 
@@ -66,8 +163,13 @@ impl<E: Ext + 'static> Environment<E> {
         memory_pages: &BTreeMap<PageNumber, Box<PageBuf>>,
         memory: &dyn Memory,
         entry_point: &str,
-    ) -> (anyhow::Result<()>, E) {
-        let module = Module::new(self.store.engine(), binary).expect("Error creating module");
+    ) -> (TerminationReason, E) {
+        // Compilation of an arbitrary (possibly malformed) binary must not abort the host: surface
+        // a failure as a structured `HostError` instead of panicking.
+        let module = match self.cached_module(binary) {
+            Ok(module) => module,
+            Err(err) => return (TerminationReason::HostError(err.to_string()), ext),
+        };
 
         self.ext.set(ext);
 
@@ -83,7 +185,99 @@ impl<E: Ext + 'static> Environment<E> {
 
         let ext = self.ext.unset();
 
-        (result, ext)
+        // A `gr_wait`-style host call unwinds the instance with the dedicated wait trap. Recognise
+        // it and capture the current memory into a snapshot so the run can be resumed later. A
+        // snapshot failure is a host-side fault, not a silent empty resume state.
+        let reason = match termination_reason(result) {
+            TerminationReason::Trap(trap) if is_wait_trap(&trap) => match capture_snapshot(memory) {
+                Ok(snapshot) => TerminationReason::Wait(snapshot),
+                Err(err) => TerminationReason::HostError(err),
+            },
+            other => other,
+        };
+
+        (reason, ext)
+    }
+
+    /// Resume a contract that previously returned [`TerminationReason::Wait`].
+    ///
+    /// Reinstantiates the module, reloads the snapshot pages through the existing page-setting
+    /// logic, and dispatches to the wake/reply entry instead of the original entry point, so the
+    /// resumed run observes the same memory the paused one left behind.
+    pub fn resume(
+        &mut self,
+        snapshot: ExecutionSnapshot,
+        binary: &[u8],
+        memory: &dyn Memory,
+        reply_entry_point: &str,
+        ext: E,
+    ) -> (TerminationReason, E) {
+        self.setup_and_run(ext, binary, &snapshot.pages, memory, reply_entry_point)
+    }
+
+    /// Enable or disable the baseline-tracking memory restore.
+    ///
+    /// When enabled, [`run_inner`](Self::run_inner) restores the saved pages through
+    /// [`MemoryWrap::map_pages_cow`], which writes every saved page into linear memory up front
+    /// (as the eager path does) and keeps them as a baseline. Pages are not mapped copy-on-write
+    /// and not faulted in lazily. After a run, [`dirty_pages`](Self::dirty_pages) reports the pages
+    /// whose contents differ from that baseline, so only those need to be persisted.
+    pub fn set_cow_memory(&mut self, enabled: bool) {
+        self.cow_memory = enabled;
+    }
+
+    /// Pages whose contents changed during the most recent run, found by diffing against the
+    /// restored baseline.
+    ///
+    /// Only populated under [`set_cow_memory`](Self::set_cow_memory); the eager path persists by
+    /// diffing the whole memory instead.
+    pub fn dirty_pages(&self) -> &[PageNumber] {
+        &self.dirty_pages
+    }
+
+    /// Set the gas limit seeded into the store's fuel before each run.
+    ///
+    /// Only meaningful for the engine-level metering path; modules that charge through the injected
+    /// `gas` host call can leave this at its `u64::MAX` default.
+    pub fn set_gas_limit(&mut self, limit: u64) {
+        self.gas_limit = limit;
+    }
+
+    /// Fuel consumed by the most recent run (per run, not cumulative across the persistent store).
+    pub fn gas_consumed(&self) -> u64 {
+        self.store
+            .fuel_consumed()
+            .unwrap_or(0)
+            .saturating_sub(self.run_baseline)
+    }
+
+    /// Reset the persistent store's available fuel to the current gas limit before a run.
+    ///
+    /// The store is reused across messages, so `fuel_consumed` accumulates and raw `add_fuel`
+    /// would let the enforced limit drift upward. This brings the remaining fuel to exactly the
+    /// limit (capped for the unlimited default, which only needs to be large enough never to trap)
+    /// and records the post-reset baseline for [`gas_consumed`](Self::gas_consumed).
+    fn reset_fuel(&mut self) -> anyhow::Result<()> {
+        // The unlimited default still needs non-zero fuel, but must not overflow `fuel_added`.
+        let target = self.gas_limit.min(u64::MAX / 2);
+        let consumed = self.store.fuel_consumed().unwrap_or(0);
+        let remaining = self.fuel_added.saturating_sub(consumed);
+
+        if remaining < target {
+            let add = target - remaining;
+            self.store
+                .add_fuel(add)
+                .map_err(|e| anyhow::anyhow!("Can't seed store fuel: {:?}", e))?;
+            self.fuel_added = self.fuel_added.saturating_add(add);
+        } else if remaining > target {
+            // Manually burn the surplus so the next run starts at exactly the limit.
+            self.store
+                .consume_fuel(remaining - target)
+                .map_err(|e| anyhow::anyhow!("Can't reset store fuel: {:?}", e))?;
+        }
+
+        self.run_baseline = self.store.fuel_consumed().unwrap_or(0);
+        Ok(())
     }
 
     /// Return engine used by this environment.
@@ -91,6 +285,39 @@ impl<E: Ext + 'static> Environment<E> {
         self.store.engine()
     }
 
+    /// Compile `binary` once and reuse it on subsequent calls with the same code.
+    ///
+    /// On a cache miss the module is compiled with [`Module::new`] and stored under the code hash;
+    /// on a hit the already-compiled [`Module`] is cloned (a cheap `Arc` bump).
+    fn cached_module(&mut self, binary: &[u8]) -> anyhow::Result<Module> {
+        let key = code_hash(binary);
+        if let Some(module) = self.module_cache.get(&key) {
+            return Ok(module.clone());
+        }
+
+        let module = Module::new(self.store.engine(), binary)?;
+        self.module_cache.insert(key, module.clone());
+        Ok(module)
+    }
+
+    /// Compile `binary` and return a serialized artifact that can be persisted and later restored
+    /// with [`load_precompiled`](Self::load_precompiled), skipping JIT cost at execution time.
+    pub fn precompile(&self, binary: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Module::new(self.store.engine(), binary)?.serialize()
+    }
+
+    /// Restore and cache a module from an artifact produced by [`precompile`](Self::precompile).
+    ///
+    /// [`Module::deserialize`] validates the artifact against the current engine configuration and
+    /// errors on a mismatch, so a stale or foreign artifact is rejected rather than trusted.
+    pub fn load_precompiled(&mut self, binary: &[u8], artifact: &[u8]) -> anyhow::Result<()> {
+        // Safety: the artifact is validated against the engine configuration by `deserialize`,
+        // which fails on any mismatch.
+        let module = unsafe { Module::deserialize(self.store.engine(), artifact)? };
+        self.module_cache.insert(code_hash(binary), module);
+        Ok(())
+    }
+
     /// Create memory inside this environment.
     pub fn create_memory(&self, total_pages: u32) -> MemoryWrap {
         MemoryWrap::new(
@@ -109,49 +336,50 @@ impl<E: Ext + 'static> Environment<E> {
         memory: &dyn Memory,
         func: impl FnOnce(Instance) -> anyhow::Result<()>,
     ) -> anyhow::Result<()> {
-        let mut imports = module
-            .imports()
-            .map(|import| {
-                if import.module() != "env" {
-                    Err(anyhow::anyhow!("Non-env imports are not supported"))
-                } else {
-                    Ok((import.name(), Option::<Extern>::None))
-                }
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?;
-
-        for (ref import_name, ref mut ext) in imports.iter_mut() {
-            if let Some(name) = import_name {
-                *ext = match *name {
-                    "memory" => {
-                        let mem: &wasmtime::Memory =
-                            match memory.as_any().downcast_ref::<wasmtime::Memory>() {
-                                Some(mem) => mem,
-                                None => panic!("Memory is not wasmtime::Memory"),
-                            };
-                        Some(wasmtime::Extern::Memory(Clone::clone(mem)))
-                    }
-                    key if self.funcs.contains_key(key) => Some(self.funcs[key].clone().into()),
-                    _ => continue,
-                }
-            }
+        // Resolve imports through the linker: the host functions registered in `Environment::new`
+        // are already defined under `env`, and `memory` is defined per-run here. Instantiation is
+        // then a single `instantiate`, with the linker reporting any unresolved import — no linear
+        // scan and no hard-coded module-name check.
+        let mut linker = self.linker.clone();
+
+        let mem: &wasmtime::Memory = memory
+            .as_any()
+            .downcast_ref::<wasmtime::Memory>()
+            .ok_or_else(|| anyhow::anyhow!("Memory is not wasmtime::Memory"))?;
+        linker.define("env", "memory", mem.clone())?;
+
+        let instance = linker.instantiate(&module)?;
+
+        // Restore module memory. Both paths copy every saved page up front; the `map_pages_cow`
+        // path also records them as the baseline for dirty-page diffing.
+        if self.cow_memory {
+            let wrap = memory
+                .as_any()
+                .downcast_ref::<MemoryWrap>()
+                .ok_or_else(|| anyhow::anyhow!("CoW memory requires a MemoryWrap"))?;
+            wrap.map_pages_cow(memory_pages)
+                .map_err(|e| anyhow::anyhow!("Can't map module memory: {:?}", e))?;
+        } else {
+            memory
+                .set_pages(memory_pages)
+                .map_err(|e| anyhow::anyhow!("Can't set module memory: {:?}", e))?;
         }
 
-        let externs = imports
-            .into_iter()
-            .map(|(_, host_function)| {
-                host_function.ok_or_else(|| anyhow::anyhow!("Missing import"))
-            })
-            .collect::<anyhow::Result<Vec<_>>>()?;
+        // Reset the store's fuel for the engine-level metering path. Even the unlimited default is
+        // topped up, because a `consume_fuel` store starts at zero and would otherwise trap on the
+        // first instruction.
+        self.reset_fuel()?;
 
-        let instance = Instance::new(&self.store, &module, &externs)?;
+        let result = func(instance);
 
-        // Set module memory.
-        memory
-            .set_pages(memory_pages)
-            .map_err(|e| anyhow::anyhow!("Can't set module memory: {:?}", e))?;
+        // Collect the pages changed during the run so only those get persisted.
+        if self.cow_memory {
+            if let Some(wrap) = memory.as_any().downcast_ref::<MemoryWrap>() {
+                self.dirty_pages = wrap.take_dirty_pages();
+            }
+        }
 
-        func(instance)
+        result
     }
 
     fn add_func_i32<F>(&mut self, key: &'static str, func: fn(LaterExt<E>) -> F)
@@ -274,6 +502,62 @@ impl<E: Ext + 'static> Environment<E> {
     }
 }
 
+/// `blake2b-256` digest of a contract binary, used as the module cache key.
+fn code_hash(binary: &[u8]) -> [u8; 32] {
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update(binary);
+    hasher.finalize().into()
+}
+
+/// Read the live pages out of `memory` into a snapshot at a wait point.
+///
+/// Relies on [`MemoryWrap`] to read back the pages it holds. A memory that is not a
+/// `MemoryWrap` cannot be snapshotted, so this fails loudly rather than returning an empty snapshot
+/// that would silently resume the contract with blank memory.
+fn capture_snapshot(memory: &dyn Memory) -> Result<ExecutionSnapshot, String> {
+    let wrap = memory.as_any().downcast_ref::<MemoryWrap>().ok_or_else(|| {
+        "cannot snapshot a waiting contract: memory is not a `MemoryWrap`".to_string()
+    })?;
+    Ok(ExecutionSnapshot {
+        pages: wrap.read_pages(),
+    })
+}
+
+/// Turn a raw run result into a [`TerminationReason`].
+///
+/// A clean return is [`Success`](TerminationReason::Success). Fuel exhaustion surfaces as a
+/// wasmtime trap whose code is `None` and whose message is "all fuel consumed" (it is *not*
+/// `TrapCode::Interrupt`, which is epoch interruption); it becomes
+/// [`OutOfGas`](TerminationReason::OutOfGas). The wait trap ([`wait_trap`]) is left as
+/// [`Trap`](TerminationReason::Trap) so [`setup_and_run`](Environment::setup_and_run) can resolve it
+/// to [`Wait`](TerminationReason::Wait) once it has the memory in hand.
+///
+/// The remaining traps are split so callers can tell a genuine fault from a host-initiated exit: a
+/// trap carrying a [`TrapCode`](wasmtime::TrapCode) is a real wasm fault and is kept as
+/// [`Trap`](TerminationReason::Trap) with its backtrace intact, while a code-less trap is one the
+/// host `wrap1..wrap5` helpers raised from a `Result<_, &'static str>` and is reported as a
+/// structured [`HostError`](TerminationReason::HostError). A non-trap error is likewise a
+/// [`HostError`](TerminationReason::HostError).
+fn termination_reason(result: anyhow::Result<()>) -> TerminationReason {
+    let err = match result {
+        Ok(()) => return TerminationReason::Success,
+        Err(err) => err,
+    };
+
+    match err.downcast::<Trap>() {
+        Ok(trap) if is_out_of_fuel(&trap) => TerminationReason::OutOfGas,
+        Ok(trap) if is_wait_trap(&trap) => TerminationReason::Trap(trap),
+        Ok(trap) if trap.trap_code().is_some() => TerminationReason::Trap(trap),
+        Ok(trap) => TerminationReason::HostError(trap.to_string()),
+        Err(err) => TerminationReason::HostError(err.to_string()),
+    }
+}
+
+/// Whether a trap is wasmtime's fuel-exhaustion trap.
+fn is_out_of_fuel(trap: &Trap) -> bool {
+    trap.trap_code().is_none() && trap.to_string().contains("all fuel consumed")
+}
+
 impl<E: Ext + 'static> Default for Environment<E> {
     /// Creates a default environment.
     fn default() -> Self {
@@ -0,0 +1,148 @@
+//! Linear memory wrapper with a dirty-page-tracking restore path.
+//!
+//! [`MemoryWrap`] wraps the `wasmtime::Memory` backing a running instance. The guest reaches linear
+//! memory directly through JIT loads and stores, not through the `Memory` trait, so there is no
+//! place for this wrapper to intercept an individual access without an OS page-fault handler
+//! (`userfaultfd`) installed on the backing store — which this wrapper does not own. Rather than
+//! pretend to fault pages in lazily, both restore paths write the saved pages into the real backing
+//! store up front so the running contract observes correct memory.
+//!
+//! What the copy-on-write entry point ([`map_pages_cow`](MemoryWrap::map_pages_cow)) buys over the
+//! plain [`set_pages`](MemoryWrap::set_pages) path is *persistence granularity*: it keeps the
+//! restored pages as a baseline and, after the run, [`take_dirty_pages`](MemoryWrap::take_dirty_pages)
+//! diffs linear memory against that baseline so only the pages the contract actually changed are
+//! written back, instead of persisting the whole memory.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+
+use gear_core::memory::{Error, Memory, PageBuf, PageNumber};
+
+/// Linear memory with a baseline kept for post-run dirty-page diffing.
+pub struct MemoryWrap {
+    mem: wasmtime::Memory,
+    /// Contents the pages were restored with, kept so the written pages can be recovered by diffing
+    /// linear memory after the run. Pages absent from the map are treated as zero.
+    baseline: RefCell<BTreeMap<PageNumber, Box<PageBuf>>>,
+}
+
+impl MemoryWrap {
+    /// Wrap a freshly created `wasmtime::Memory`.
+    pub fn new(mem: wasmtime::Memory) -> Self {
+        Self {
+            mem,
+            baseline: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Restore `pages` and remember them as the baseline for dirty-page diffing.
+    ///
+    /// The saved pages are written into the real backing store the JIT reads and writes, exactly as
+    /// [`set_pages`](MemoryWrap::set_pages) does, so memory semantics are identical. The difference
+    /// is that the baseline is retained: [`take_dirty_pages`](MemoryWrap::take_dirty_pages) then
+    /// reports only the pages changed during the run.
+    pub fn map_pages_cow(&self, pages: &BTreeMap<PageNumber, Box<PageBuf>>) -> Result<(), Error> {
+        self.restore(pages)
+    }
+
+    /// Pages whose contents differ from the restored baseline, i.e. those written during the run.
+    pub fn take_dirty_pages(&self) -> Vec<PageNumber> {
+        let baseline = self.baseline.borrow();
+        let zero = PageBuf::new_zeroed();
+        let mut dirty = Vec::new();
+        for page in self.live_pages() {
+            let current = match self.read_page(page) {
+                Ok(buf) => buf,
+                Err(_) => continue,
+            };
+            let saved = baseline
+                .get(&page)
+                .map(|buf| buf.as_slice())
+                .unwrap_or_else(|| zero.as_slice());
+            if current.as_slice() != saved {
+                dirty.push(page);
+            }
+        }
+        dirty
+    }
+
+    /// Read back every page that carries content from the live linear memory.
+    ///
+    /// A wait snapshot must reflect exactly what the paused contract saw, so this scans the whole
+    /// backing store rather than trusting any page-tracking: it returns every restored page plus any
+    /// page a run wrote away from zero, so a resumed run observes identical memory. Untouched zero
+    /// pages are omitted to keep the snapshot compact.
+    pub fn read_pages(&self) -> BTreeMap<PageNumber, Box<PageBuf>> {
+        let baseline = self.baseline.borrow();
+        let zero = PageBuf::new_zeroed();
+        let mut pages = BTreeMap::new();
+        for page in self.live_pages() {
+            let buf = match self.read_page(page) {
+                Ok(buf) => buf,
+                Err(_) => continue,
+            };
+            if baseline.contains_key(&page) || buf.as_slice() != zero.as_slice() {
+                pages.insert(page, buf);
+            }
+        }
+        pages
+    }
+
+    /// Write every saved page into linear memory and record the baseline.
+    fn restore(&self, pages: &BTreeMap<PageNumber, Box<PageBuf>>) -> Result<(), Error> {
+        for (page, buf) in pages {
+            self.write_page(*page, buf)?;
+        }
+        *self.baseline.borrow_mut() = pages.clone();
+        Ok(())
+    }
+
+    /// Every page index currently backed by linear memory.
+    fn live_pages(&self) -> impl Iterator<Item = PageNumber> {
+        let count = (self.mem.data_size() / PageNumber::size()) as u32;
+        (0..count).map(PageNumber::from)
+    }
+
+    /// Copy a single saved page into linear memory at its byte offset.
+    fn write_page(&self, page: PageNumber, buf: &PageBuf) -> Result<(), Error> {
+        let offset = page.offset();
+        unsafe {
+            let data = &mut self.mem.data_unchecked_mut()[offset..offset + buf.len()];
+            data.copy_from_slice(buf.as_slice());
+        }
+        Ok(())
+    }
+
+    /// Read a single page back out of linear memory.
+    fn read_page(&self, page: PageNumber) -> Result<Box<PageBuf>, Error> {
+        let offset = page.offset();
+        let mut buf = Box::new(PageBuf::new_zeroed());
+        unsafe {
+            let data = &self.mem.data_unchecked()[offset..offset + buf.len()];
+            buf.as_mut_slice().copy_from_slice(data);
+        }
+        Ok(buf)
+    }
+}
+
+impl Memory for MemoryWrap {
+    /// Eagerly restore every saved page into linear memory and record the baseline.
+    fn set_pages(&self, pages: &BTreeMap<PageNumber, Box<PageBuf>>) -> Result<(), Error> {
+        self.restore(pages)
+    }
+
+    fn read(&self, page: PageNumber) -> Result<Box<PageBuf>, Error> {
+        self.read_page(page)
+    }
+
+    fn write(&self, page: PageNumber, buf: &PageBuf) -> Result<(), Error> {
+        self.write_page(page, buf)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
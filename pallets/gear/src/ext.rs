@@ -60,6 +60,13 @@ impl BackendExt for LazyPagesExt {
         self.inner.context.gas_counter.clone().into()
     }
 
+    /// Pre-fault and charge a whole access set in one call.
+    ///
+    /// The full `reads`/`writes` slices are handed to [`lazy_pages::pre_process_memory_accesses`],
+    /// which resolves the not-yet-loaded pages and charges their load gas against the metering
+    /// globals as a batch. Charging lives in the lazy-pages runtime — it owns the globals config and
+    /// the fault handler, so it is the only layer that can both bill the load and suppress the
+    /// subsequent per-page fault charge; doing it here over `&mut self` would double-charge.
     fn pre_process_memory_accesses(
         reads: &[MemoryInterval],
         writes: &[MemoryInterval],
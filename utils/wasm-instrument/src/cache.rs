@@ -0,0 +1,172 @@
+// This file is part of Gear.
+
+// Copyright (C) 2021-2023 Gear Technologies Inc.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Instrumentation cache for [`inject`](crate::inject).
+//!
+//! `inject` is pure given `(raw_code, rules, gas_module_name)`, so its result can be memoized.
+//! Entries are keyed by a `blake2b-256` digest of `gas_module_name` and `raw_code` combined with a
+//! stable version of the `Rules` implementation, meaning a rules change transparently invalidates
+//! the matching entries. The
+//! instrumented module is shared behind an [`Arc`] out of a bounded LRU, mirroring the prepared
+//! module pools kept by fuel-metered WASM runtimes.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use blake2::{digest::typenum::U32, Blake2b, Digest};
+use wasm_instrument::{
+    gas_metering::Rules,
+    parity_wasm::{self, elements},
+};
+
+use crate::inject;
+
+/// Default number of instrumented modules kept resident in the cache.
+pub const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// Rules that can take part in instrumentation caching.
+///
+/// The returned version must change whenever the instruction costs produced by the `Rules`
+/// implementation change, otherwise stale instrumentation could be served from the cache.
+pub trait CacheableRules: Rules {
+    /// Stable identifier of this rules configuration.
+    fn rules_version(&self) -> u64;
+}
+
+type CodeHash = [u8; 32];
+type CacheKey = (CodeHash, u64);
+
+/// Error returned by [`cached_inject`] when the underlying `inject` call fails.
+#[derive(Debug)]
+pub enum CacheError {
+    /// Raw code could not be deserialized into a module.
+    Deserialize,
+    /// `inject` rejected the module (see [`inject`] for the possible reasons).
+    Inject,
+}
+
+struct Lru {
+    capacity: usize,
+    map: HashMap<CacheKey, Arc<elements::Module>>,
+    // Least-recently-used first, most-recently-used last.
+    order: Vec<CacheKey>,
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            map: HashMap::new(),
+            order: Vec::new(),
+        }
+    }
+
+    fn touch(&mut self, key: &CacheKey) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos);
+            self.order.push(key);
+        }
+    }
+
+    fn get(&mut self, key: &CacheKey) -> Option<Arc<elements::Module>> {
+        let value = self.map.get(key).cloned();
+        if value.is_some() {
+            self.touch(key);
+        }
+        value
+    }
+
+    fn insert(&mut self, key: CacheKey, value: Arc<elements::Module>) {
+        if self.map.insert(key, value).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push(key);
+        while self.order.len() > self.capacity {
+            let evicted = self.order.remove(0);
+            self.map.remove(&evicted);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.order.clear();
+    }
+}
+
+static CACHE: Mutex<Option<Lru>> = Mutex::new(None);
+
+fn with_cache<T>(f: impl FnOnce(&mut Lru) -> T) -> T {
+    let mut guard = CACHE.lock().expect("instrumentation cache poisoned");
+    let lru = guard.get_or_insert_with(|| Lru::new(DEFAULT_CACHE_CAPACITY));
+    f(lru)
+}
+
+fn code_hash(code: &[u8], gas_module_name: &str) -> CodeHash {
+    // `inject` embeds `gas_module_name` as the import module of the out-of-resources call(s), so it
+    // is part of the instrumentation identity and must be folded into the key. A length prefix
+    // keeps the name and code unambiguously separated.
+    let mut hasher = Blake2b::<U32>::new();
+    hasher.update((gas_module_name.len() as u64).to_le_bytes());
+    hasher.update(gas_module_name.as_bytes());
+    hasher.update(code);
+    hasher.finalize().into()
+}
+
+/// Instrument `code`, reusing a previously instrumented module when one is cached.
+///
+/// On a miss the raw code is deserialized and passed through [`inject`]; the resulting module is
+/// stored behind an [`Arc`] and returned. The cache key combines the code hash, the
+/// `gas_module_name` embedded in the instrumentation, and [`CacheableRules::rules_version`], so a
+/// change to any of them serves fresh instrumentation without an explicit [`clear`].
+pub fn cached_inject<R: CacheableRules>(
+    code: &[u8],
+    rules: &R,
+    gas_module_name: &str,
+) -> Result<Arc<elements::Module>, CacheError> {
+    let key = (code_hash(code, gas_module_name), rules.rules_version());
+
+    if let Some(module) = with_cache(|lru| lru.get(&key)) {
+        return Ok(module);
+    }
+
+    let module =
+        parity_wasm::deserialize_buffer(code).map_err(|_| CacheError::Deserialize)?;
+    let instrumented = inject(module, rules, gas_module_name).map_err(|_| CacheError::Inject)?;
+    let instrumented = Arc::new(instrumented);
+
+    with_cache(|lru| lru.insert(key, instrumented.clone()));
+
+    Ok(instrumented)
+}
+
+/// Pre-warm the cache with `code` so a later [`cached_inject`] is a hit.
+pub fn prewarm<R: CacheableRules>(
+    code: &[u8],
+    rules: &R,
+    gas_module_name: &str,
+) -> Result<(), CacheError> {
+    cached_inject(code, rules, gas_module_name).map(|_| ())
+}
+
+/// Drop every cached instrumented module.
+pub fn clear() {
+    with_cache(|lru| lru.clear());
+}
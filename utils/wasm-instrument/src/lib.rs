@@ -37,29 +37,91 @@ pub use wasm_instrument::{self, parity_wasm};
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "std")]
+pub mod cache;
+pub mod memory_grow;
+pub mod mutable_global;
 pub mod rules;
 pub mod syscalls;
 
+/// Extension over [`Rules`] exposing the per-page cost of `memory.grow`.
+///
+/// Unlike the flat per-instruction costs returned by [`Rules`], `memory.grow` should be billed in
+/// proportion to the page delta it requests (see [`memory_grow`]). Implementors override
+/// [`memory_grow_per_page_cost`](GrowRules::memory_grow_per_page_cost) to enable per-page
+/// metering; the default of `0` keeps the flat per-instruction rate.
+pub trait GrowRules: Rules {
+    /// Gas charged for each page requested by a `memory.grow` instruction.
+    fn memory_grow_per_page_cost(&self) -> u32 {
+        0
+    }
+}
+
 pub const GLOBAL_NAME_GAS: &str = "gear_gas";
 pub const GLOBAL_NAME_ALLOWANCE: &str = "gear_allowance";
 pub const GLOBAL_NAME_FLAGS: &str = "gear_flags";
 
+/// Import field name of the single trap call used by [`TrapMode::Flags`].
+///
+/// Kept as a local constant rather than a `SysCallName` variant so the flags scheme does not
+/// depend on the syscall enum gaining a new entry.
+pub const OUT_OF_RESOURCES_IMPORT_NAME: &str = "out_of_resources";
+
+/// `gear_flags` discriminant written before trapping on gas exhaustion.
+pub const GEAR_FLAG_OUT_OF_GAS: i32 = 1;
+/// `gear_flags` discriminant written before trapping on allowance exhaustion.
+pub const GEAR_FLAG_OUT_OF_ALLOWANCE: i32 = 2;
+
+/// How the instrumented code signals a metering trap back to the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrapMode {
+    /// Write a discriminant into the `gear_flags` global, then call a single `out_of_resources`
+    /// import. The host reads `gear_flags` to recover the exact reason, which keeps a single import
+    /// and leaves room for future reason codes.
+    Flags,
+    /// Emit the two legacy `out_of_gas`/`out_of_allowance` imports, for hosts that predate the
+    /// `gear_flags` scheme.
+    LegacyImports,
+}
+
 /// '__gear_stack_end' export is inserted by wasm-proc or wasm-builder,
 /// it indicates the end of program stack memory.
 pub const STACK_END_EXPORT_NAME: &str = "__gear_stack_end";
 
-pub fn inject<R: Rules>(
+/// Indices and the in-progress builder produced by [`prepare`].
+///
+/// Both metering back ends (the function-call back end in [`inject`] and the inlined back end in
+/// [`inject_mut_global`]) share the same prologue: the `gear_gas`/`gear_allowance` globals, their
+/// exports, and the out-of-resources import(s). [`prepare`] performs that once and hands the
+/// indices back so each back end can emit its own body instrumentation.
+///
+/// Under [`TrapMode::Flags`] a single `out_of_resources` import is pushed (so
+/// `out_of_gas_index == out_of_allowance_index`) together with the `gear_flags` global, whose
+/// index is returned in `flags_index`. Under [`TrapMode::LegacyImports`] the two distinct legacy
+/// imports are pushed and `flags_index` is `None`.
+struct Prepared {
+    builder: builder::ModuleBuilder,
+    out_of_gas_index: u32,
+    out_of_allowance_index: u32,
+    gas_index: u32,
+    allowance_index: u32,
+    flags_index: Option<u32>,
+    gas_charge_index: u32,
+}
+
+fn prepare(
     module: elements::Module,
-    rules: &R,
     gas_module_name: &str,
-) -> Result<elements::Module, elements::Module> {
+    trap_mode: TrapMode,
+) -> Result<Prepared, elements::Module> {
     if module
         .import_section()
         .map(|section| {
             section.entries().iter().any(|entry| {
                 entry.module() == gas_module_name
                     && (entry.field() == SysCallName::OutOfGas.to_str()
-                        || entry.field() == SysCallName::OutOfAllowance.to_str())
+                        || entry.field() == SysCallName::OutOfAllowance.to_str()
+                        || entry.field() == OUT_OF_RESOURCES_IMPORT_NAME)
             })
         })
         .unwrap_or(false)
@@ -84,34 +146,56 @@ pub fn inject<R: Rules>(
     // fn out_of_...() -> ();
     let import_sig = mbuilder.push_signature(builder::signature().build_sig());
 
-    mbuilder.push_import(
-        builder::import()
-            .module(gas_module_name)
-            .field(SysCallName::OutOfGas.to_str())
-            .external()
-            .func(import_sig)
-            .build(),
-    );
-
-    mbuilder.push_import(
-        builder::import()
-            .module(gas_module_name)
-            .field(SysCallName::OutOfAllowance.to_str())
-            .external()
-            .func(import_sig)
-            .build(),
-    );
+    match trap_mode {
+        TrapMode::Flags => {
+            mbuilder.push_import(
+                builder::import()
+                    .module(gas_module_name)
+                    .field(OUT_OF_RESOURCES_IMPORT_NAME)
+                    .external()
+                    .func(import_sig)
+                    .build(),
+            );
+        }
+        TrapMode::LegacyImports => {
+            mbuilder.push_import(
+                builder::import()
+                    .module(gas_module_name)
+                    .field(SysCallName::OutOfGas.to_str())
+                    .external()
+                    .func(import_sig)
+                    .build(),
+            );
+
+            mbuilder.push_import(
+                builder::import()
+                    .module(gas_module_name)
+                    .field(SysCallName::OutOfAllowance.to_str())
+                    .external()
+                    .func(import_sig)
+                    .build(),
+            );
+        }
+    }
 
     // back to plain module
     let module = mbuilder.build();
 
-    let import_count = module.import_count(elements::ImportCountType::Function);
-    let out_of_gas_index = import_count as u32 - 2;
-    let out_of_allowance_index = import_count as u32 - 1;
+    let import_count = module.import_count(elements::ImportCountType::Function) as u32;
+    let (out_of_gas_index, out_of_allowance_index) = match trap_mode {
+        // A single shared import carries both reasons; the discriminant in `gear_flags` tells them
+        // apart.
+        TrapMode::Flags => (import_count - 1, import_count - 1),
+        TrapMode::LegacyImports => (import_count - 2, import_count - 1),
+    };
 
-    let gas_charge_index = module.functions_space();
+    let gas_charge_index = module.functions_space() as u32;
     let gas_index = module.globals_space() as u32;
     let allowance_index = gas_index + 1;
+    let flags_index = match trap_mode {
+        TrapMode::Flags => Some(allowance_index + 1),
+        TrapMode::LegacyImports => None,
+    };
 
     let mut mbuilder = builder::from_module(module);
 
@@ -149,6 +233,72 @@ pub fn inject<R: Rules>(
             .build(),
     );
 
+    if let Some(flags_index) = flags_index {
+        mbuilder.push_global(
+            builder::global()
+                .value_type()
+                .i32()
+                .init_expr(Instruction::I32Const(0))
+                .mutable()
+                .build(),
+        );
+
+        mbuilder.push_export(
+            builder::export()
+                .field(GLOBAL_NAME_FLAGS)
+                .internal()
+                .global(flags_index)
+                .build(),
+        );
+    }
+
+    Ok(Prepared {
+        builder: mbuilder,
+        out_of_gas_index,
+        out_of_allowance_index,
+        gas_index,
+        allowance_index,
+        flags_index,
+        gas_charge_index,
+    })
+}
+
+/// Instrument `module` with the function-call metering back end.
+///
+/// This intentionally defaults to [`TrapMode::Flags`]: the `gear_flags` scheme is the new trap
+/// path, and hosts that have not adopted it opt back into the two legacy imports through
+/// [`inject_with_trap_mode`] with [`TrapMode::LegacyImports`]. Switching the default therefore
+/// changes the imports emitted for current hosts by design — the compatibility mode is the
+/// migration path, not the default.
+pub fn inject<R: Rules>(
+    module: elements::Module,
+    rules: &R,
+    gas_module_name: &str,
+) -> Result<elements::Module, elements::Module> {
+    inject_with_trap_mode(module, rules, gas_module_name, TrapMode::Flags)
+}
+
+/// Like [`inject`], but selects how metering traps are signalled to the host.
+///
+/// [`TrapMode::Flags`] (the default used by [`inject`]) writes a discriminant into `gear_flags`
+/// and calls a single `out_of_resources` import; [`TrapMode::LegacyImports`] keeps the two
+/// separate imports for hosts that have not adopted the flags scheme.
+pub fn inject_with_trap_mode<R: Rules>(
+    module: elements::Module,
+    rules: &R,
+    gas_module_name: &str,
+    trap_mode: TrapMode,
+) -> Result<elements::Module, elements::Module> {
+    let Prepared {
+        builder: mut mbuilder,
+        out_of_gas_index,
+        out_of_allowance_index,
+        gas_index,
+        allowance_index,
+        flags_index,
+        gas_charge_index,
+    } = prepare(module, gas_module_name, trap_mode)?;
+
     let mut elements = vec![
         // check if there is enough gas
         Instruction::GetGlobal(gas_index),
@@ -203,6 +353,31 @@ pub fn inject<R: Rules>(
         Instruction::End,
     ];
 
+    if let Some(flags_index) = flags_index {
+        // Write the termination discriminant into `gear_flags` immediately before the shared
+        // `out_of_resources` trap call, so the host can recover the exact reason. The first trap
+        // call belongs to the gas branch, the second to the allowance branch.
+        let mut seen = 0u32;
+        let mut i = 0;
+        while i < elements.len() {
+            if matches!(elements[i], Instruction::Call(idx) if idx == out_of_gas_index) {
+                let flag = if seen == 0 {
+                    GEAR_FLAG_OUT_OF_GAS
+                } else {
+                    GEAR_FLAG_OUT_OF_ALLOWANCE
+                };
+                elements.splice(
+                    i..i,
+                    [Instruction::I32Const(flag), Instruction::SetGlobal(flags_index)],
+                );
+                seen += 1;
+                i += 3;
+                continue;
+            }
+            i += 1;
+        }
+    }
+
     // determine cost for successful execution
     let mut block_of_code = false;
 
@@ -272,5 +447,118 @@ pub fn inject<R: Rules>(
     // back to plain module
     let module = mbuilder.build();
 
-    gas_metering::post_injection_handler(module, rules, gas_charge_index, out_of_gas_index, 2)
+    let added_imports = match trap_mode {
+        TrapMode::Flags => 1,
+        TrapMode::LegacyImports => 2,
+    };
+
+    gas_metering::post_injection_handler(
+        module,
+        rules,
+        gas_charge_index,
+        out_of_gas_index,
+        added_imports,
+    )
+}
+
+/// Instrument `module` with the inlined, mutable-global metering back end.
+///
+/// Gas-conservative relative to [`inject`], but **not** bit-identical: instead of emitting a
+/// `Call(gas_charge)` per metered block it folds each block's cost into a constant and inlines the
+/// charge directly (see [`mutable_global`]). Because it charges at structured-block granularity it
+/// may over-charge a block that branches out early, so gas totals will not match [`inject`]'s
+/// per-metered-block accounting — callers comparing the two (e.g. a differential metering check)
+/// must not assume equal totals. This trades a larger code section for the removal of the
+/// per-block call and its prologue, so [`inject`] remains the right choice for size-sensitive
+/// modules while this one wins on execution speed for hot code.
+pub fn inject_mut_global<R: Rules>(
+    module: elements::Module,
+    rules: &R,
+    gas_module_name: &str,
+) -> Result<elements::Module, elements::Module> {
+    let Prepared {
+        builder: mbuilder,
+        out_of_gas_index,
+        out_of_allowance_index,
+        gas_index,
+        allowance_index,
+        // The inlined back end keeps the two legacy imports and has no separate charge function.
+        flags_index: _,
+        gas_charge_index: _,
+    } = prepare(module, gas_module_name, TrapMode::LegacyImports)?;
+
+    let mut module = mbuilder.build();
+
+    // Two imported functions were prepended to the defined-function index space, so every
+    // reference to a previously-defined function shifts by two. Imported-function references
+    // (indices below the original import count) are untouched.
+    let shift_threshold = out_of_gas_index;
+    relocate_function_indices(&mut module, shift_threshold, 2);
+
+    let targets = mutable_global::Targets {
+        gas_index,
+        allowance_index,
+        out_of_gas_index,
+        out_of_allowance_index,
+    };
+
+    if let Some(code) = module.code_section_mut() {
+        for body in code.bodies_mut() {
+            let instrumented = match mutable_global::inject_counter(
+                body.code().elements(),
+                rules,
+                &targets,
+            ) {
+                Some(instructions) => instructions,
+                None => return Err(module.clone()),
+            };
+            *body.code_mut() = elements::Instructions::new(instrumented);
+        }
+    }
+
+    Ok(module)
+}
+
+/// Shift every function-index reference that is `>= threshold` by `shift`.
+///
+/// Covers the references affected by prepending imported functions: `Call` operands in code, the
+/// `start` function, exported functions, and function indices embedded in element segments.
+fn relocate_function_indices(module: &mut elements::Module, threshold: u32, shift: u32) {
+    let bump = |index: &mut u32| {
+        if *index >= threshold {
+            *index += shift;
+        }
+    };
+
+    if let Some(code) = module.code_section_mut() {
+        for body in code.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let Instruction::Call(index) = instruction {
+                    bump(index);
+                }
+            }
+        }
+    }
+
+    if let Some(start) = module.start_section() {
+        let mut start = start;
+        bump(&mut start);
+        module.set_start_section(start);
+    }
+
+    if let Some(exports) = module.export_section_mut() {
+        for entry in exports.entries_mut() {
+            if let elements::Internal::Function(index) = entry.internal_mut() {
+                bump(index);
+            }
+        }
+    }
+
+    if let Some(elements_section) = module.elements_section_mut() {
+        for segment in elements_section.entries_mut() {
+            for index in segment.members_mut() {
+                bump(index);
+            }
+        }
+    }
 }
@@ -0,0 +1,235 @@
+// This file is part of Gear.
+
+// Copyright (C) 2021-2023 Gear Technologies Inc.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-page metering of `memory.grow`.
+//!
+//! The flat per-instruction rate undercharges `memory.grow`, whose real cost scales with the page
+//! delta requested. [`meter_memory_grow`] runs as a post-pass over an already instrumented module
+//! (see [`inject`](crate::inject) / [`inject_mut_global`](crate::inject_mut_global)): for every
+//! `memory.grow` it reads the requested page count off the top of the stack, multiplies it by
+//! [`GrowRules::memory_grow_per_page_cost`], and charges the product against the `gear_gas` and
+//! `gear_allowance` globals *before* the grow runs. Because `memory.grow` can fail and return
+//! `-1`, this bills for the attempt; a refund path can later be layered on through the counters
+//! API.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use wasm_instrument::parity_wasm::elements::{
+    self, BlockType, Instruction, Instructions, Internal, Local, ValueType,
+};
+
+use crate::{
+    syscalls::SysCallName, GrowRules, GEAR_FLAG_OUT_OF_ALLOWANCE, GEAR_FLAG_OUT_OF_GAS,
+    GLOBAL_NAME_ALLOWANCE, GLOBAL_NAME_FLAGS, GLOBAL_NAME_GAS, OUT_OF_RESOURCES_IMPORT_NAME,
+};
+
+/// Indices recovered by name from an already instrumented module.
+///
+/// The trap path is whichever [`inject`](crate::inject) emitted. Under the default `Flags` scheme a
+/// single `out_of_resources` import carries both reasons and `flags_index` is `Some`, so the charge
+/// writes the discriminant into `gear_flags` before calling it; under `LegacyImports` the two
+/// distinct imports are used and `flags_index` is `None`.
+struct Anchors {
+    gas_index: u32,
+    allowance_index: u32,
+    flags_index: Option<u32>,
+    out_of_gas_index: u32,
+    out_of_allowance_index: u32,
+}
+
+fn recover_anchors(module: &elements::Module) -> Option<Anchors> {
+    let global_index = |field: &str| {
+        module.export_section()?.entries().iter().find_map(|entry| {
+            match entry.internal() {
+                Internal::Global(index) if entry.field() == field => Some(*index),
+                _ => None,
+            }
+        })
+    };
+
+    let mut func_import = 0u32;
+    let mut out_of_gas_index = None;
+    let mut out_of_allowance_index = None;
+    let mut out_of_resources_index = None;
+    if let Some(imports) = module.import_section() {
+        for entry in imports.entries() {
+            if let elements::External::Function(_) = entry.external() {
+                if entry.field() == OUT_OF_RESOURCES_IMPORT_NAME {
+                    out_of_resources_index = Some(func_import);
+                } else if entry.field() == SysCallName::OutOfGas.to_str() {
+                    out_of_gas_index = Some(func_import);
+                } else if entry.field() == SysCallName::OutOfAllowance.to_str() {
+                    out_of_allowance_index = Some(func_import);
+                }
+                func_import += 1;
+            }
+        }
+    }
+
+    // `Flags` modules import the single shared trap; `LegacyImports` modules import the two
+    // distinct ones. Recognise either so grow metering is not dropped for the default scheme.
+    let (out_of_gas_index, out_of_allowance_index, flags_index) = match out_of_resources_index {
+        Some(shared) => (shared, shared, Some(global_index(GLOBAL_NAME_FLAGS)?)),
+        None => (out_of_gas_index?, out_of_allowance_index?, None),
+    };
+
+    Some(Anchors {
+        gas_index: global_index(GLOBAL_NAME_GAS)?,
+        allowance_index: global_index(GLOBAL_NAME_ALLOWANCE)?,
+        flags_index,
+        out_of_gas_index,
+        out_of_allowance_index,
+    })
+}
+
+/// Emit the trap call for one exhaustion reason, writing the `gear_flags` discriminant first when
+/// the module uses the shared `Flags` trap.
+fn trap_call(trap_index: u32, flags_index: Option<u32>, flag: i32) -> Vec<Instruction> {
+    let mut out = Vec::with_capacity(4);
+    if let Some(flags_index) = flags_index {
+        out.push(Instruction::I32Const(flag));
+        out.push(Instruction::SetGlobal(flags_index));
+    }
+    out.push(Instruction::Call(trap_index));
+    out.push(Instruction::Unreachable);
+    out
+}
+
+/// Inline charge for a single `memory.grow`, using `$pages`/`$cost` scratch locals.
+fn grow_charge(per_page_cost: u32, pages: u32, cost: u32, anchors: &Anchors) -> Vec<Instruction> {
+    let mut out = vec![
+        // $pages = page count (kept on the stack for the following `memory.grow`)
+        Instruction::TeeLocal(pages),
+        // $cost = $pages * per_page_cost, computed in i64 so the product cannot wrap in 32 bits
+        Instruction::GetLocal(pages),
+        Instruction::I64ExtendUI32,
+        Instruction::I64Const(per_page_cost as i64),
+        Instruction::I64Mul,
+        Instruction::SetLocal(cost),
+        // gas: if gas < $cost { trap(out_of_gas) } gas -= $cost
+        Instruction::GetGlobal(anchors.gas_index),
+        Instruction::GetLocal(cost),
+        Instruction::I64LtU,
+        Instruction::If(BlockType::NoResult),
+    ];
+    out.extend(trap_call(
+        anchors.out_of_gas_index,
+        anchors.flags_index,
+        GEAR_FLAG_OUT_OF_GAS,
+    ));
+    out.extend([
+        Instruction::End,
+        Instruction::GetGlobal(anchors.gas_index),
+        Instruction::GetLocal(cost),
+        Instruction::I64Sub,
+        Instruction::SetGlobal(anchors.gas_index),
+        // allowance: analogous sequence
+        Instruction::GetGlobal(anchors.allowance_index),
+        Instruction::GetLocal(cost),
+        Instruction::I64LtU,
+        Instruction::If(BlockType::NoResult),
+    ]);
+    out.extend(trap_call(
+        anchors.out_of_allowance_index,
+        anchors.flags_index,
+        GEAR_FLAG_OUT_OF_ALLOWANCE,
+    ));
+    out.extend([
+        Instruction::End,
+        Instruction::GetGlobal(anchors.allowance_index),
+        Instruction::GetLocal(cost),
+        Instruction::I64Sub,
+        Instruction::SetGlobal(anchors.allowance_index),
+    ]);
+    out
+}
+
+/// Number of value-stack parameters each defined function declares, in body order.
+fn param_counts(module: &elements::Module) -> Vec<u32> {
+    let types = module.type_section().map(|s| s.types()).unwrap_or(&[]);
+    module
+        .function_section()
+        .map(|s| s.entries())
+        .unwrap_or(&[])
+        .iter()
+        .map(|func| match types.get(func.type_ref() as usize) {
+            Some(elements::Type::Function(ty)) => ty.params().len() as u32,
+            None => 0,
+        })
+        .collect()
+}
+
+/// Charge `memory.grow` per requested page across every function body of `module`.
+///
+/// This is a best-effort post-pass: a [`GrowRules::memory_grow_per_page_cost`] of `0`, or a module
+/// that carries no metering anchors (gas/allowance globals and a trap import — a module that was
+/// never gas-instrumented has nothing to charge against), leaves the module untouched. Missing
+/// anchors are therefore *not* an error; the module is returned unchanged.
+pub fn meter_memory_grow<R: GrowRules>(
+    mut module: elements::Module,
+    rules: &R,
+) -> elements::Module {
+    let per_page_cost = rules.memory_grow_per_page_cost();
+    if per_page_cost == 0 {
+        return module;
+    }
+
+    let anchors = match recover_anchors(&module) {
+        Some(anchors) => anchors,
+        None => return module,
+    };
+
+    let params = param_counts(&module);
+
+    let code = match module.code_section_mut() {
+        Some(code) => code,
+        None => return module,
+    };
+
+    for (body_index, body) in code.bodies_mut().iter_mut().enumerate() {
+        if !body
+            .code()
+            .elements()
+            .iter()
+            .any(|i| matches!(i, Instruction::GrowMemory(_)))
+        {
+            continue;
+        }
+
+        let locals_total: u32 = body.locals().iter().map(|l| l.count()).sum();
+        let base = params.get(body_index).copied().unwrap_or(0) + locals_total;
+        let pages_local = base;
+        let cost_local = base + 1;
+
+        body.locals_mut().push(Local::new(1, ValueType::I32));
+        body.locals_mut().push(Local::new(1, ValueType::I64));
+
+        let instructions = body.code().elements().to_vec();
+        let mut output = Vec::with_capacity(instructions.len());
+        for instruction in instructions {
+            if matches!(instruction, Instruction::GrowMemory(_)) {
+                output.extend(grow_charge(per_page_cost, pages_local, cost_local, &anchors));
+            }
+            output.push(instruction);
+        }
+        *body.code_mut() = Instructions::new(output);
+    }
+
+    module
+}
@@ -0,0 +1,165 @@
+// This file is part of Gear.
+
+// Copyright (C) 2021-2023 Gear Technologies Inc.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Inlined, mutable-global metering back end for [`inject_mut_global`](crate::inject_mut_global).
+//!
+//! Instead of emitting a `Call(gas_charge)` per metered block, this back end folds the block cost
+//! into a compile-time constant and inlines the charge directly into the block. That removes the
+//! call and its prologue — the cost that dominates cheap blocks — at the price of a larger body,
+//! so the function-call back end in [`inject`](crate::inject) stays available for size-sensitive
+//! modules.
+//!
+//! Charging happens at structured-block granularity: the cost of every instruction directly
+//! contained in a block (function body, `block`, `loop`, `if`/`else` arm) is summed and charged
+//! once on entry to that block. Charging upfront can only over-charge a block that later branches
+//! out early, which is safe.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use wasm_instrument::{gas_metering::Rules, parity_wasm::elements::Instruction};
+
+/// Globals and imports an inlined charge sequence refers to.
+pub(crate) struct Targets {
+    pub gas_index: u32,
+    pub allowance_index: u32,
+    pub out_of_gas_index: u32,
+    pub out_of_allowance_index: u32,
+}
+
+/// Emit the inlined charge sequence for a single block cost.
+///
+/// Mirrors the function-call back end's body: guard the global, trap through the relevant import
+/// on exhaustion, then subtract. A zero cost produces no instructions.
+fn charge(cost: u64, targets: &Targets) -> Vec<Instruction> {
+    if cost == 0 {
+        return Vec::new();
+    }
+
+    let cost = cost as i64;
+    vec![
+        // gas: if gas < cost { out_of_gas(); unreachable } gas -= cost
+        Instruction::GetGlobal(targets.gas_index),
+        Instruction::I64Const(cost),
+        Instruction::I64LtU,
+        Instruction::If(wasm_instrument::parity_wasm::elements::BlockType::NoResult),
+        Instruction::Call(targets.out_of_gas_index),
+        Instruction::Unreachable,
+        Instruction::End,
+        Instruction::GetGlobal(targets.gas_index),
+        Instruction::I64Const(cost),
+        Instruction::I64Sub,
+        Instruction::SetGlobal(targets.gas_index),
+        // allowance: analogous sequence
+        Instruction::GetGlobal(targets.allowance_index),
+        Instruction::I64Const(cost),
+        Instruction::I64LtU,
+        Instruction::If(wasm_instrument::parity_wasm::elements::BlockType::NoResult),
+        Instruction::Call(targets.out_of_allowance_index),
+        Instruction::Unreachable,
+        Instruction::End,
+        Instruction::GetGlobal(targets.allowance_index),
+        Instruction::I64Const(cost),
+        Instruction::I64Sub,
+        Instruction::SetGlobal(targets.allowance_index),
+    ]
+}
+
+/// Per-block accumulator tracked while scanning a function body.
+struct Block {
+    /// Position in the output vector where this block's charge sequence must be spliced in.
+    insert_at: usize,
+    /// Accumulated cost of the instructions directly contained in the block.
+    cost: u64,
+}
+
+/// Rewrite a single function body, inlining a charge sequence at the head of every block.
+///
+/// Returns `None` when a cost cannot be determined or the overflow guard
+/// (`cost > u64::MAX - u32::MAX`, carried over from the function-call back end) trips.
+pub(crate) fn inject_counter<R: Rules>(
+    instructions: &[Instruction],
+    rules: &R,
+    targets: &Targets,
+) -> Option<Vec<Instruction>> {
+    // Rebuild the body, reserving a slot right after each block opener for its charge sequence.
+    let mut output: Vec<Instruction> = Vec::with_capacity(instructions.len());
+    let mut stack: Vec<Block> = Vec::new();
+
+    // Function body is itself a block; its charge goes to the very front.
+    output.push(Instruction::Nop);
+    stack.push(Block {
+        insert_at: 0,
+        cost: 0,
+    });
+
+    let mut charges: Vec<(usize, u64)> = Vec::new();
+
+    for instruction in instructions {
+        let cost = rules.instruction_cost(instruction)? as u64;
+
+        if let Some(block) = stack.last_mut() {
+            block.cost = block.cost.checked_add(cost)?;
+        }
+
+        match instruction {
+            Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => {
+                output.push(instruction.clone());
+                output.push(Instruction::Nop);
+                stack.push(Block {
+                    insert_at: output.len() - 1,
+                    cost: 0,
+                });
+            }
+            Instruction::Else => {
+                // Close the current arm and open a fresh one for the else branch.
+                let block = stack.pop()?;
+                charges.push((block.insert_at, block.cost));
+                output.push(instruction.clone());
+                output.push(Instruction::Nop);
+                stack.push(Block {
+                    insert_at: output.len() - 1,
+                    cost: 0,
+                });
+            }
+            Instruction::End => {
+                let block = stack.pop()?;
+                charges.push((block.insert_at, block.cost));
+                output.push(instruction.clone());
+            }
+            _ => output.push(instruction.clone()),
+        }
+    }
+
+    // The outermost function block is closed by the trailing `End` handled above, so the stack is
+    // empty here for a well-formed body.
+    for (_, cost) in &charges {
+        if *cost > u64::MAX - u64::from(u32::MAX) {
+            return None;
+        }
+    }
+
+    // Splice charge sequences in from the back so earlier insert positions stay valid.
+    charges.sort_by(|a, b| b.0.cmp(&a.0));
+    for (at, cost) in charges {
+        let seq = charge(cost, targets);
+        output.splice(at..=at, seq);
+    }
+
+    Some(output)
+}